@@ -1,13 +1,35 @@
-use std::io::{Read, Cursor, Result};
-use byteorder::{ReadBytesExt, LE};
+use std::io::{Read, Seek, SeekFrom, Cursor, Result, Error, ErrorKind};
+use byteorder::{ReadBytesExt, LE, BE};
 use compress::zlib::Decoder;
 
+//runtime-selected byte order, for big-endian console ports and cross-tool interop
+#[derive(Copy, Clone)]
+pub enum Endian {
+	Little,
+	Big,
+}
+
 pub use tr_derive::Readable;
+//a struct tagged #[readable(args = SomeType)] derives ReadableArgs<SomeType> instead, and fields
+//tagged #[readable(if = "expr")] (expr sees a reference to the args as `version`) become Option<T>, read only
+//when the condition holds; #[readable(args = Endian)] is special-cased to thread the chosen
+//endian recursively into every field via ReadableArgs<Endian>, so the whole struct flips endian
 
 pub trait Readable {
 	fn read<R: Read>(reader: &mut R) -> Result<Self> where Self: Sized;
 }
 
+//like Readable, but threads a caller-supplied param (game version, endian, ...) down into the read
+pub trait ReadableArgs<P> {
+	fn read<R: Read>(reader: &mut R, params: P) -> Result<Self> where Self: Sized;
+}
+
+impl<T: Readable> ReadableArgs<()> for T {
+	fn read<R: Read>(reader: &mut R, _params: ()) -> Result<Self> {
+		<T as Readable>::read(reader)
+	}
+}
+
 macro_rules! impl_readable_prim {
 	($type:ty, $func:ident $(, $($endian:tt)*)?) => {
 		impl Readable for $type {
@@ -35,14 +57,67 @@ impl_readable_prim_le!(i64, read_i64);
 impl_readable_prim_le!(f32, read_f32);
 impl_readable_prim_le!(f64, read_f64);
 
+macro_rules! impl_readable_args_endian_single_byte {
+	($type:ty, $func:ident) => {
+		impl ReadableArgs<Endian> for $type {
+			fn read<R: Read>(reader: &mut R, _endian: Endian) -> Result<Self> {
+				reader.$func()
+			}
+		}
+	};
+}
+
+macro_rules! impl_readable_args_endian {
+	($type:ty, $func:ident) => {
+		impl ReadableArgs<Endian> for $type {
+			fn read<R: Read>(reader: &mut R, endian: Endian) -> Result<Self> {
+				match endian {
+					Endian::Little => reader.$func::<LE>(),
+					Endian::Big => reader.$func::<BE>(),
+				}
+			}
+		}
+	};
+}
+
+impl_readable_args_endian_single_byte!(u8, read_u8);
+impl_readable_args_endian_single_byte!(i8, read_i8);
+impl_readable_args_endian!(u16, read_u16);
+impl_readable_args_endian!(i16, read_i16);
+impl_readable_args_endian!(u32, read_u32);
+impl_readable_args_endian!(i32, read_i32);
+impl_readable_args_endian!(u64, read_u64);
+impl_readable_args_endian!(i64, read_i64);
+impl_readable_args_endian!(f32, read_f32);
+impl_readable_args_endian!(f64, read_f64);
+
+//cap eager Vec::with_capacity reserves against untrusted declared lengths; push grows the rest
+const MAX_ALLOC_LEN: usize = 0x10000;
+
 pub fn read_vec<R: Read, T: Readable>(reader: &mut R, len: usize) -> Result<Vec<T>> {
-	let mut vec = Vec::with_capacity(len);
+	let mut vec = Vec::with_capacity(len.min(MAX_ALLOC_LEN));
 	for _ in 0..len {
 		vec.push(T::read(reader)?);
 	}
 	Ok(vec)
 }
 
+pub fn read_vec_args<R: Read, T: ReadableArgs<P>, P: Clone>(reader: &mut R, len: usize, params: P) -> Result<Vec<T>> {
+	let mut vec = Vec::with_capacity(len.min(MAX_ALLOC_LEN));
+	for _ in 0..len {
+		vec.push(T::read(reader, params.clone())?);
+	}
+	Ok(vec)
+}
+
+pub fn read_with_endian<R: Read, T: ReadableArgs<Endian>>(reader: &mut R, endian: Endian) -> Result<T> {
+	T::read(reader, endian)
+}
+
+pub fn read_vec_with_endian<R: Read, T: ReadableArgs<Endian>>(reader: &mut R, len: usize, endian: Endian) -> Result<Vec<T>> {
+	read_vec_args(reader, len, endian)
+}
+
 impl<T: Readable, const N: usize> Readable for [T; N] {
 	fn read<R: Read>(reader: &mut R) -> Result<Self> {
 		Ok(read_vec(reader, N)?.try_into().ok().unwrap())//reads exactly N items
@@ -57,6 +132,7 @@ impl<T: Readable, const N: usize> Readable for Box<[T; N]> {
 
 pub trait Len {
 	fn read_len<R: Read>(reader: &mut R) -> Result<usize>;
+	fn read_len_with_endian<R: Read>(reader: &mut R, endian: Endian) -> Result<usize>;
 }
 
 macro_rules! impl_len {
@@ -65,6 +141,13 @@ macro_rules! impl_len {
 			fn read_len<R: Read>(reader: &mut R) -> Result<usize> {
 				Ok(reader.$func::<LE>()? as usize)
 			}
+
+			fn read_len_with_endian<R: Read>(reader: &mut R, endian: Endian) -> Result<usize> {
+				Ok(match endian {
+					Endian::Little => reader.$func::<LE>()?,
+					Endian::Big => reader.$func::<BE>()?,
+				} as usize)
+			}
 		}
 	};
 }
@@ -77,6 +160,16 @@ pub fn read_list<R: Read, T: Readable, L: Len>(reader: &mut R) -> Result<Vec<T>>
 	read_vec(reader, len)
 }
 
+pub fn read_list_args<R: Read, T: ReadableArgs<P>, L: Len, P: Clone>(reader: &mut R, params: P) -> Result<Vec<T>> {
+	let len = L::read_len(reader)?;
+	read_vec_args(reader, len, params)
+}
+
+pub fn read_list_with_endian<R: Read, T: ReadableArgs<Endian>, L: Len>(reader: &mut R, endian: Endian) -> Result<Vec<T>> {
+	let len = L::read_len_with_endian(reader, endian)?;
+	read_vec_with_endian(reader, len, endian)
+}
+
 pub fn read_list_2d<R: Read, T: Readable>(reader: &mut R) -> Result<Vec<Vec<T>>> {
 	let len1 = u16::read_len(reader)?;
 	let len2 = u16::read_len(reader)?;
@@ -87,37 +180,223 @@ pub fn read_list_2d<R: Read, T: Readable>(reader: &mut R) -> Result<Vec<Vec<T>>>
 	Ok(vec)
 }
 
+pub fn read_list_2d_with_endian<R: Read, T: ReadableArgs<Endian>>(reader: &mut R, endian: Endian) -> Result<Vec<Vec<T>>> {
+	let len1 = u16::read_len_with_endian(reader, endian)?;
+	let len2 = u16::read_len_with_endian(reader, endian)?;
+	let mut vec = Vec::with_capacity(len1);
+	for _ in 0..len1 {
+		vec.push(read_vec_with_endian(reader, len2, endian)?);
+	}
+	Ok(vec)
+}
+
+//like Readable, but can signal "no more elements" instead of erroring
+pub trait MaybeReadable {
+	fn read<R: Read>(reader: &mut R) -> Result<Option<Self>> where Self: Sized;
+}
+
+impl<T: Readable> MaybeReadable for T {
+	fn read<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+		Ok(Some(<T as Readable>::read(reader)?))
+	}
+}
+
+struct CountingReader<'a, R> {
+	inner: &'a mut R,
+	bytes_read: u64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		let num_read = self.inner.read(buf)?;
+		self.bytes_read += num_read as u64;
+		Ok(num_read)
+	}
+}
+
+//reads T until a clean EOF lands on an element boundary; a truncated final element is an error
+pub fn read_until_end<R: Read, T: MaybeReadable>(reader: &mut R) -> Result<Vec<T>> {
+	read_until_end_with(reader, |_, _| Ok(()))
+}
+
+//as read_until_end, also running after_item(reader, element_len) after each element is read
+pub fn read_until_end_with<R: Read, T: MaybeReadable>(
+	reader: &mut R,
+	mut after_item: impl FnMut(&mut R, u64) -> Result<()>,
+) -> Result<Vec<T>> {
+	let mut vec = Vec::new();
+	loop {
+		let mut counting = CountingReader { inner: reader, bytes_read: 0 };
+		let mut peek = [0; 1];
+		if counting.read(&mut peek)? == 0 {
+			break;//clean eof right at an element boundary
+		}
+		let mut chained = Cursor::new(peek).chain(&mut counting);
+		match T::read(&mut chained)? {
+			Some(item) => vec.push(item),
+			None => break,
+		}
+		let bytes_read = counting.bytes_read;
+		after_item(reader, bytes_read)?;
+	}
+	Ok(vec)
+}
+
 pub fn read_meshes<R: Read, T: Readable>(reader: &mut R) -> Result<Vec<T>> {
 	let num_bytes = u32::read_len(reader)? * 2;
 	let bytes = read_vec::<_, u8>(reader, num_bytes)?;
 	let mut cursor = Cursor::new(bytes);
-	let mut vec = Vec::new();
-	let num_bytes = num_bytes as u64;
-	loop {
-		let pos1 = cursor.position();
-		if num_bytes - pos1 == 0 {
-			break;
+	read_until_end_with(&mut cursor, |cursor, bytes_read| {
+		if bytes_read % 4 != 0 {
+			skip::<_, 2>(cursor)
+		} else {
+			Ok(())
 		}
-		vec.push(T::read(&mut cursor)?);
-		let pos2 = cursor.position();
-		if (pos2 - pos1) % 4 != 0 {
-			cursor.set_position(pos2 + 2);
+	})
+}
+
+//wraps a reader with a total byte budget: reads are capped to the budget and see a clean EOF
+//once it's exhausted, so a nested Readable can't read into whatever follows the length-delimited
+//section in the outer stream; read_exact-style callers still get UnexpectedEof if they demand
+//more than remains, and eat_remaining errors outright if the outer stream can't supply the
+//declared budget in full
+pub struct FixedLengthReader<R> {
+	reader: R,
+	len: u64,
+	bytes_read: u64,
+}
+
+impl<R: Read> FixedLengthReader<R> {
+	pub fn new(reader: R, len: u64) -> Self {
+		FixedLengthReader { reader, len, bytes_read: 0 }
+	}
+
+	pub fn bytes_read(&self) -> u64 {
+		self.bytes_read
+	}
+
+	pub fn bytes_remain(&self) -> u64 {
+		self.len - self.bytes_read
+	}
+
+	//consumes and discards whatever's left of the budget, erroring if the underlying reader
+	//can't actually supply that many bytes
+	pub fn eat_remaining(&mut self) -> Result<()> {
+		let mut buf = [0; 256];
+		while self.bytes_remain() > 0 {
+			let len = self.bytes_remain().min(buf.len() as u64) as usize;
+			self.read_exact(&mut buf[..len])?;
 		}
+		Ok(())
+	}
+}
+
+impl<R: Read> Read for FixedLengthReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		if self.bytes_remain() == 0 {
+			return Ok(0);
+		}
+		let max_len = (buf.len() as u64).min(self.bytes_remain()) as usize;
+		let num_read = self.reader.read(&mut buf[..max_len])?;
+		self.bytes_read += num_read as u64;
+		Ok(num_read)
 	}
-	Ok(vec)
 }
 
-pub fn get_zlib<R: Read>(reader: &mut R) -> Result<Decoder<Cursor<Vec<u8>>>> {
-	u32::read_len(reader)?;//uncompressed_len
+//decodes eagerly (rather than returning a lazy Decoder) so the uncompressed length can be
+//checked against the stored value before handing data back to the caller
+pub fn get_zlib<R: Read>(reader: &mut R) -> Result<Cursor<Vec<u8>>> {
+	let uncompressed_len = u32::read_len(reader)?;
 	let compressed_len = u32::read_len(reader)?;
-	let bytes = read_vec::<_, u8>(reader, compressed_len)?;
-	Ok(Decoder::new(Cursor::new(bytes)))
+	let fixed_reader = FixedLengthReader::new(reader, compressed_len as u64);
+	let mut decoder = Decoder::new(fixed_reader);
+	let mut uncompressed = Vec::with_capacity(uncompressed_len.min(MAX_ALLOC_LEN));
+	decoder.read_to_end(&mut uncompressed)?;
+	if uncompressed.len() != uncompressed_len {
+		return Err(Error::new(ErrorKind::InvalidData, "zlib uncompressed length mismatch"));
+	}
+	//the deflate stream ends before compressed_len (trailer/padding); drain it so the outer
+	//reader lands exactly on the next section regardless of what the decoder left unread
+	decoder.unwrap().eat_remaining()?;
+	Ok(Cursor::new(uncompressed))
 }
 
+//reads through a stack scratch buffer rather than one byte at a time
 pub fn skip<R: Read, const N: usize>(reader: &mut R) -> Result<()> {
-	let mut buf = [0];
-	for _ in 0..N {
-		reader.read_exact(&mut buf)?;
+	let mut buf = [0; 256];
+	let mut remain = N;
+	while remain > 0 {
+		let len = remain.min(buf.len());
+		reader.read_exact(&mut buf[..len])?;
+		remain -= len;
 	}
 	Ok(())
 }
+
+//for seekable sources, skip with a single relative seek instead of reading and discarding bytes
+pub fn skip_seek<R: Read + Seek, const N: usize>(reader: &mut R) -> Result<()> {
+	reader.seek(SeekFrom::Current(N as i64))?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_until_end_reads_all_elements_on_clean_eof() {
+		let bytes = vec![1, 0, 2, 0, 3, 0];//three little-endian u16s, no trailing bytes
+		let mut cursor = Cursor::new(bytes);
+		let items: Vec<u16> = read_until_end(&mut cursor).unwrap();
+		assert_eq!(items, vec![1, 2, 3]);
+	}
+
+	#[test]
+	fn read_until_end_errors_on_truncated_final_element() {
+		let bytes = vec![1, 0, 2, 0, 3];//trailing single byte: a u16 started but not finished
+		let mut cursor = Cursor::new(bytes);
+		let result: Result<Vec<u16>> = read_until_end(&mut cursor);
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn read_until_end_with_runs_hook_with_element_len() {
+		let bytes = vec![1, 0, 2, 0];//two u16s
+		let mut cursor = Cursor::new(bytes);
+		let mut lens = Vec::new();
+		let items: Vec<u16> = read_until_end_with(&mut cursor, |_, len| {
+			lens.push(len);
+			Ok(())
+		}).unwrap();
+		assert_eq!(items, vec![1, 2]);
+		assert_eq!(lens, vec![2, 2]);
+	}
+
+	#[test]
+	fn fixed_length_reader_stops_at_budget_even_if_more_bytes_follow() {
+		let bytes = vec![1, 2, 3, 4, 5];
+		let mut cursor = Cursor::new(bytes);
+		let mut fixed = FixedLengthReader::new(&mut cursor, 3);
+		let mut out = Vec::new();
+		fixed.read_to_end(&mut out).unwrap();
+		assert_eq!(out, vec![1, 2, 3]);
+		assert_eq!(fixed.bytes_remain(), 0);
+	}
+
+	#[test]
+	fn fixed_length_reader_eat_remaining_errors_on_short_underlying_stream() {
+		let bytes = vec![1, 2];
+		let mut cursor = Cursor::new(bytes);
+		let mut fixed = FixedLengthReader::new(&mut cursor, 5);
+		assert!(fixed.eat_remaining().is_err());
+	}
+
+	#[test]
+	fn fixed_length_reader_eat_remaining_consumes_exact_budget() {
+		let bytes = vec![1, 2, 3];
+		let mut cursor = Cursor::new(bytes);
+		let mut fixed = FixedLengthReader::new(&mut cursor, 3);
+		fixed.eat_remaining().unwrap();
+		assert_eq!(fixed.bytes_remain(), 0);
+	}
+}
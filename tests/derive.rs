@@ -0,0 +1,56 @@
+use std::io::Cursor;
+use tr_readable::{Endian, Readable, ReadableArgs};
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+enum GameVersion {
+	Tr1,
+	Tr4,
+}
+
+#[derive(Readable)]
+#[readable(args = GameVersion)]
+struct Mesh {
+	flags: u16,
+	#[readable(if = "*version >= GameVersion::Tr4")]
+	light_color: Option<u32>,
+}
+
+#[test]
+fn version_gated_field_is_read_when_condition_holds() {
+	let bytes = vec![0x34, 0x12, 0xEF, 0xBE, 0xAD, 0xDE];//flags, then a gated u32
+	let mut cursor = Cursor::new(bytes);
+	let mesh = Mesh::read(&mut cursor, GameVersion::Tr4).unwrap();
+	assert_eq!(mesh.flags, 0x1234);
+	assert_eq!(mesh.light_color, Some(0xDEADBEEF));
+}
+
+#[test]
+fn version_gated_field_is_skipped_below_threshold() {
+	let bytes = vec![0x34, 0x12];//just flags, no gated field on disk
+	let mut cursor = Cursor::new(bytes);
+	let mesh = Mesh::read(&mut cursor, GameVersion::Tr1).unwrap();
+	assert_eq!(mesh.flags, 0x1234);
+	assert_eq!(mesh.light_color, None);
+}
+
+#[derive(Readable)]
+#[readable(args = Endian)]
+struct Point {
+	x: i16,
+	y: i16,
+}
+
+#[test]
+fn derived_struct_threads_endian_into_every_field() {
+	let le_bytes = vec![0x01, 0x00, 0x02, 0x00];
+	let mut cursor = Cursor::new(le_bytes);
+	let point = Point::read(&mut cursor, Endian::Little).unwrap();
+	assert_eq!(point.x, 1);
+	assert_eq!(point.y, 2);
+
+	let be_bytes = vec![0x00, 0x01, 0x00, 0x02];
+	let mut cursor = Cursor::new(be_bytes);
+	let point = Point::read(&mut cursor, Endian::Big).unwrap();
+	assert_eq!(point.x, 1);
+	assert_eq!(point.y, 2);
+}
@@ -0,0 +1,102 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, LitStr, Path};
+
+//parses #[readable(args = SomeType)] off the struct itself
+fn struct_args(input: &DeriveInput) -> Option<Path> {
+	let mut args = None;
+	for attr in &input.attrs {
+		if attr.path().is_ident("readable") {
+			attr.parse_nested_meta(|meta| {
+				if meta.path.is_ident("args") {
+					args = Some(meta.value()?.parse::<Path>()?);
+				}
+				Ok(())
+			}).expect("malformed #[readable(...)] attribute");
+		}
+	}
+	args
+}
+
+//parses #[readable(if = "expr")] off a field; expr is evaluated with a reference to the args in
+//scope under the name `version`, e.g. #[readable(if = "*version >= GameVersion::Tr4")]
+fn field_if_cond(attrs: &[syn::Attribute]) -> Option<Expr> {
+	let mut cond = None;
+	for attr in attrs {
+		if attr.path().is_ident("readable") {
+			attr.parse_nested_meta(|meta| {
+				if meta.path.is_ident("if") {
+					let lit = meta.value()?.parse::<LitStr>()?;
+					cond = Some(lit.parse::<Expr>()?);
+				}
+				Ok(())
+			}).expect("malformed #[readable(...)] attribute");
+		}
+	}
+	cond
+}
+
+#[proc_macro_derive(Readable, attributes(readable))]
+pub fn readable_derive(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	let name = &input.ident;
+	let args = struct_args(&input);
+	//ReadableArgs<Endian> is implemented by hand for every primitive, so an args = Endian struct
+	//can thread the chosen endian recursively into each field; any other args type is only
+	//consulted by #[readable(if = ...)] field gates, not passed down into ordinary field reads
+	let is_endian_args = args.as_ref().is_some_and(|path| path.is_ident("Endian"));
+
+	let Data::Struct(data) = &input.data else {
+		panic!("Readable can only be derived for structs");
+	};
+	let Fields::Named(fields) = &data.fields else {
+		panic!("Readable can only be derived for structs with named fields");
+	};
+
+	let field_inits = fields.named.iter().map(|field| {
+		let field_name = field.ident.as_ref().unwrap();
+		let cond = field_if_cond(&field.attrs);
+		match (cond, is_endian_args) {
+			(Some(cond), true) => quote! {
+				#field_name: if #cond {
+					Some(::tr_readable::ReadableArgs::read(reader, params.clone())?)
+				} else {
+					None
+				}
+			},
+			(Some(cond), false) => quote! {
+				#field_name: if #cond {
+					Some(::tr_readable::Readable::read(reader)?)
+				} else {
+					None
+				}
+			},
+			(None, true) => quote! {
+				#field_name: ::tr_readable::ReadableArgs::read(reader, params.clone())?
+			},
+			(None, false) => quote! {
+				#field_name: ::tr_readable::Readable::read(reader)?
+			},
+		}
+	});
+
+	let expanded = match &args {
+		None => quote! {
+			impl ::tr_readable::Readable for #name {
+				fn read<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+					Ok(#name { #(#field_inits),* })
+				}
+			}
+		},
+		Some(args_ty) => quote! {
+			#[allow(unused_variables)]
+			impl ::tr_readable::ReadableArgs<#args_ty> for #name {
+				fn read<R: std::io::Read>(reader: &mut R, params: #args_ty) -> std::io::Result<Self> {
+					let version = &params;
+					Ok(#name { #(#field_inits),* })
+				}
+			}
+		},
+	};
+	expanded.into()
+}